@@ -5,471 +5,687 @@ use std::io::Read;
 use std::io::Write;
 use std::time::Duration;
 use std::collections::HashMap;
-use byteorder::{BigEndian, ByteOrder};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use num::ToPrimitive;
+use native_tls::{TlsConnector, TlsStream};
 
 use ::util::pretty_print_hex;
 
 const BOLT_PREAMBLE: [u8; 4] = [0x60, 0x60, 0xB0, 0x17];
-const BOLT_SUPPORTED_VERSIONS: [u32; 1] = [ 1 ];
+// Listed in descending order of preference. The handshake always offers
+// exactly `BOLT_HANDSHAKE_SLOTS` slots, with any slots past the end of this
+// list padded with `BOLT_VERSION_NONE`.
+const BOLT_SUPPORTED_VERSIONS: [u32; 3] = [ 3, 2, 1 ];
+const BOLT_HANDSHAKE_SLOTS: usize = 4;
 const BOLT_VERSION_NONE : u32 = 0;
 
+#[derive(Debug)]
+pub enum BoltError {
+    Io(io::Error),
+    Failure(HashMap<String, BoltValue>),
+    Protocol(String),
+    NoSupportedVersion,
+}
+
+impl From<io::Error> for BoltError {
+    fn from(error: io::Error) -> BoltError {
+        BoltError::Io(error)
+    }
+}
+
 trait BoltSerialize {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error> ;
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>;
+}
+
+trait BoltDeserialize: Sized {
+    fn deserialize(buf: &mut impl Read) -> Result<Self, io::Error>;
+}
+
+impl BoltDeserialize for BoltValue {
+    fn deserialize(buf: &mut impl Read) -> Result<BoltValue, io::Error> {
+        unpack(buf)
+    }
+}
+
+impl BoltDeserialize for String {
+    fn deserialize(buf: &mut impl Read) -> Result<String, io::Error> {
+        match try!(unpack(buf)) {
+            BoltValue::String(value) => Ok(value),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Expected a string")),
+        }
+    }
+}
+
+impl BoltDeserialize for Vec<BoltValue> {
+    fn deserialize(buf: &mut impl Read) -> Result<Vec<BoltValue>, io::Error> {
+        match try!(unpack(buf)) {
+            BoltValue::List(value) => Ok(value),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Expected a list")),
+        }
+    }
+}
+
+impl BoltDeserialize for HashMap<String, BoltValue> {
+    fn deserialize(buf: &mut impl Read) -> Result<HashMap<String, BoltValue>, io::Error> {
+        match try!(unpack(buf)) {
+            BoltValue::Map(value) => Ok(value),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Expected a map")),
+        }
+    }
+}
+
+// `pub` because it appears in the public `RecordStream` iterator item and
+// in `BoltError::Failure`'s metadata map.
+#[derive(Debug)]
+pub enum BoltValue {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<BoltValue>),
+    Map(HashMap<String, BoltValue>),
+    Node(Node),
+    Relationship(Relationship),
+    Path(Path),
+    Structure(u8, Vec<BoltValue>),
+}
+
+impl BoltSerialize for BoltValue {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        match *self {
+            BoltValue::Null => serialize_null(writer),
+            BoltValue::Boolean(value) => serialize_boolean(writer, value),
+            BoltValue::Integer(value) => serialize_integer(writer, value),
+            BoltValue::Float(value) => serialize_float(writer, value),
+            BoltValue::String(ref value) => serialize_string(writer, value),
+            BoltValue::List(ref value) => serialize_list(writer, value),
+            BoltValue::Map(ref value) => serialize_map(writer, value),
+            BoltValue::Node(ref value) => serialize_node(writer, value),
+            BoltValue::Relationship(ref value) => serialize_relationship(writer, value),
+            BoltValue::Path(ref value) => serialize_path(writer, value),
+            BoltValue::Structure(signature, ref fields) => {
+                try!(write_struct_header(writer, fields.len() as i32));
+                try!(writer.write_u8(signature));
+                for field in fields {
+                    try!(field.serialize(writer));
+                }
+                Ok(())
+            },
+        }
+    }
 }
 
 impl BoltSerialize for Null {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        Ok(serialize_null())
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_null(writer)
     }
 }
 
 impl BoltSerialize for bool {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        Ok(serialize_boolean(*self))
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_boolean(writer, *self)
     }
 }
 
 impl BoltSerialize for i8 {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_integer(*self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_integer(writer, *self)
     }
 }
 
 impl BoltSerialize for i16 {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_integer(*self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_integer(writer, *self)
     }
 }
 
 impl BoltSerialize for i32 {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_integer(*self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_integer(writer, *self)
     }
 }
 
 impl BoltSerialize for i64 {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_integer(*self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_integer(writer, *self)
     }
 }
 
 impl BoltSerialize for u8 {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_integer(*self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_integer(writer, *self)
     }
 }
 
 impl BoltSerialize for u16 {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_integer(*self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_integer(writer, *self)
     }
 }
 
 impl BoltSerialize for u32 {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_integer(*self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_integer(writer, *self)
     }
 }
 
 impl BoltSerialize for u64 {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_integer(*self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_integer(writer, *self)
     }
 }
 
 impl BoltSerialize for f64 {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        Ok(serialize_float(*self))
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_float(writer, *self)
     }
 }
 
 impl<'a> BoltSerialize for &'a str {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_string(&self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_string(writer, self)
+    }
+}
+
+impl BoltSerialize for String {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_string(writer, self)
     }
 }
 
 impl<T: BoltSerialize> BoltSerialize for Vec<T> {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_list(&self)
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_list(writer, self)
     }
 }
 
-impl<'a, T: BoltSerialize> BoltSerialize for HashMap<&'a str, T> {
-    fn serialize(&self) -> Result<Vec<u8>, io::Error>  {
-        serialize_map(&self)
+impl<T: BoltSerialize> BoltSerialize for HashMap<String, T> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_map(writer, self)
     }
 }
 
 struct Null;
 
-struct Node<T: BoltSerialize, Y: BoltSerialize> {
+// `pub` because `BoltValue` wraps these and is itself part of the public
+// `RecordStream`/`BoltError` surface, which cannot name a private type.
+// Their fields stay private to this module.
+#[derive(Debug)]
+pub struct Node {
     node_identity: u64,
-    labels: Vec<T>,
-    properties: HashMap<String, Y>,
+    labels: Vec<String>,
+    properties: HashMap<String, BoltValue>,
 }
 
-struct Relationship<T: BoltSerialize> {
+#[derive(Debug)]
+pub struct Relationship {
     rel_identity: u64,
     start_node_identity: u64,
     end_node_identity: u64,
     rel_type: String,
-    properties: HashMap<String, T>
+    properties: HashMap<String, BoltValue>,
 }
 
-struct Path<T: BoltSerialize, Y: BoltSerialize, Z: BoltSerialize> {
-    nodes: Vec<Node<T, Y>>,
-    relationships: Vec<UnboundRelationship<Z>>,
+#[derive(Debug)]
+pub struct Path {
+    nodes: Vec<Node>,
+    relationships: Vec<UnboundRelationship>,
     sequence: Vec<u64>,
 }
 
-struct UnboundRelationship<T: BoltSerialize> {
+#[derive(Debug)]
+pub struct UnboundRelationship {
     rel_identity: u64,
     rel_type: String,
-    properties: HashMap<String, T>,
+    properties: HashMap<String, BoltValue>,
+}
+
+impl BoltSerialize for Node {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_node(writer, self)
+    }
+}
+
+impl BoltSerialize for Relationship {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_relationship(writer, self)
+    }
+}
+
+impl BoltSerialize for UnboundRelationship {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_unbound_relationship(writer, self)
+    }
 }
 
-fn serialize_null() -> Vec<u8> {
-    vec![0xC0]
+impl BoltSerialize for Path {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>  {
+        serialize_path(writer, self)
+    }
+}
+
+fn serialize_null<W: Write>(writer: &mut W) -> Result<(), io::Error> {
+    try!(writer.write_u8(0xC0));
+    Ok(())
 }
 
-fn serialize_boolean(value: bool) -> Vec<u8> {
-    if value { vec![0xC3] } else { vec![0xC2] }
+fn serialize_boolean<W: Write>(writer: &mut W, value: bool) -> Result<(), io::Error> {
+    try!(writer.write_u8(if value { 0xC3 } else { 0xC2 }));
+    Ok(())
 }
 
-fn serialize_integer<T: ToPrimitive>(value: T) -> Result<Vec<u8>, io::Error> {
+fn serialize_integer<W: Write, T: ToPrimitive>(writer: &mut W, value: T) -> Result<(), io::Error> {
     match value.to_i64().unwrap() {
         value_i64 @ -9223372036854775808 ... -2147483649 | value_i64 @ 2147483648 ... 9223372036854775807 => {
-            let mut buf = [0x0; 8];
-            BigEndian::write_i64(&mut buf, value_i64);
-            let mut v = vec![0xCB];
-            v.extend_from_slice(&buf);
-            Ok(v)
+            try!(writer.write_u8(0xCB));
+            try!(writer.write_i64::<BigEndian>(value_i64));
         },
         -2147483648 ... -32769 | 32768 ... 2147483647 => {
-            let mut buf = [0x0; 4];
-            BigEndian::write_i32(&mut buf, value.to_i32().unwrap());
-            let mut v = vec![0xCA];
-            v.extend_from_slice(&buf);
-            Ok(v)
+            try!(writer.write_u8(0xCA));
+            try!(writer.write_i32::<BigEndian>(value.to_i32().unwrap()));
         },
         -32768 ... -129 | 128 ... 32767 => {
-            let mut buf = [0x0; 2];
-            BigEndian::write_i16(&mut buf, value.to_i16().unwrap());
-            let mut v = vec![0xC9];
-            v.extend_from_slice(&buf);
-            Ok(v)
+            try!(writer.write_u8(0xC9));
+            try!(writer.write_i16::<BigEndian>(value.to_i16().unwrap()));
         },
-        -128 ... -17 => Ok(vec![0xC8, value.to_i8().unwrap() as u8]),
-        -16 ... 127 => Ok(vec![value.to_i8().unwrap() as u8]),
-        _ => Err(io::Error::new(io::ErrorKind::Other, "Integer too large")),
+        -128 ... -17 => {
+            try!(writer.write_u8(0xC8));
+            try!(writer.write_i8(value.to_i8().unwrap()));
+        },
+        -16 ... 127 => try!(writer.write_i8(value.to_i8().unwrap())),
+        _ => return Err(io::Error::new(io::ErrorKind::Other, "Integer too large")),
     }
+
+    Ok(())
 }
 
-fn serialize_float(value: f64) -> Vec<u8> {
-    let mut buf = [0x0; 8];
-    BigEndian::write_f64(&mut buf, value);
-    let mut v = vec![0xC1];
-    v.extend_from_slice(&buf);
-    v
+fn serialize_float<W: Write>(writer: &mut W, value: f64) -> Result<(), io::Error> {
+    try!(writer.write_u8(0xC1));
+    try!(writer.write_f64::<BigEndian>(value));
+    Ok(())
 }
 
-fn serialize_string(s: &str) -> Result<Vec<u8>, io::Error> {
-    let mut message = match s.len() {
-        len @ 0 ... 15 => vec![0x80 + (len as u8)],
-        len @ 16 ... 255 => vec![0xD0, len as u8],
+fn serialize_string<W: Write>(writer: &mut W, s: &str) -> Result<(), io::Error> {
+    match s.len() {
+        len @ 0 ... 15 => try!(writer.write_u8(0x80 + (len as u8))),
+        len @ 16 ... 255 => {
+            try!(writer.write_u8(0xD0));
+            try!(writer.write_u8(len as u8));
+        },
         len @ 256 ... 65535 => {
-            let mut buf = [0x0; 2];
-            BigEndian::write_u16(&mut buf, len as u16);
-            let mut v = vec![0xD1];
-            v.extend_from_slice(&buf);
-            v
+            try!(writer.write_u8(0xD1));
+            try!(writer.write_u16::<BigEndian>(len as u16));
         },
         len @ 65536 ... 4294967295 => {
-            let mut buf = [0x0; 4];
-            BigEndian::write_u32(&mut buf, len as u32);
-            let mut v = vec![0xD2];
-            v.extend_from_slice(&buf);
-            v
+            try!(writer.write_u8(0xD2));
+            try!(writer.write_u32::<BigEndian>(len as u32));
         },
         _ => return Err(io::Error::new(io::ErrorKind::Other, "String too large")),
     };
 
-    message.extend_from_slice(s.as_bytes());
-    Ok(message)
+    try!(writer.write_all(s.as_bytes()));
+    Ok(())
 }
 
-fn serialize_list<T: BoltSerialize>(list: &Vec<T>) -> Result<Vec<u8>, io::Error> {
-    let mut message = match list.len() {
-        len @ 0 ... 15 => vec![0x90 + (len as u8)],
-        len @ 16 ... 255 => vec![0xD4, len as u8],
+fn serialize_list<W: Write, T: BoltSerialize>(writer: &mut W, list: &Vec<T>) -> Result<(), io::Error> {
+    match list.len() {
+        len @ 0 ... 15 => try!(writer.write_u8(0x90 + (len as u8))),
+        len @ 16 ... 255 => {
+            try!(writer.write_u8(0xD4));
+            try!(writer.write_u8(len as u8));
+        },
         len @ 256 ... 65535 => {
-            let mut v = vec![0xD5];
-            let mut buf = [0x0; 2];
-            BigEndian::write_u16(&mut buf, len as u16);
-            v.extend_from_slice(&buf);
-            v
+            try!(writer.write_u8(0xD5));
+            try!(writer.write_u16::<BigEndian>(len as u16));
         },
         len @ 65536 ... 4294967295 => {
-            let mut v = vec![0xD6];
-            let mut buf = [0x0; 4];
-            BigEndian::write_u32(&mut buf, len as u32);
-            v.extend_from_slice(&buf);
-            v
+            try!(writer.write_u8(0xD6));
+            try!(writer.write_u32::<BigEndian>(len as u32));
         },
         _ => return Err(io::Error::new(io::ErrorKind::Other, "List too large")),
     };
 
     for entry in list {
-        message.append(&mut try!(entry.serialize()));
+        try!(entry.serialize(writer));
     }
-    Ok(message)
+
+    Ok(())
 }
 
-fn serialize_map<T: BoltSerialize>(map: &HashMap<&str, T>) -> Result<Vec<u8>, io::Error> {
-    let mut message = match map.len() {
-        len @ 0 ... 15 => vec![0xA0 + (len as u8)],
-        len @ 16 ... 255 => vec![0xD8, len as u8],
+fn serialize_map<W: Write, T: BoltSerialize>(writer: &mut W, map: &HashMap<String, T>) -> Result<(), io::Error> {
+    match map.len() {
+        len @ 0 ... 15 => try!(writer.write_u8(0xA0 + (len as u8))),
+        len @ 16 ... 255 => {
+            try!(writer.write_u8(0xD8));
+            try!(writer.write_u8(len as u8));
+        },
         len @ 256 ... 65535 => {
-            let mut v = vec![0xD9];
-            let mut buf = [0x0; 2];
-            BigEndian::write_u16(&mut buf, len as u16);
-            v.extend_from_slice(&buf);
-            v
+            try!(writer.write_u8(0xD9));
+            try!(writer.write_u16::<BigEndian>(len as u16));
         },
         len @ 65536 ... 4294967295 => {
-            let mut v = vec![0xDA];
-            let mut buf = [0x0; 4];
-            BigEndian::write_u32(&mut buf, len as u32);
-            v.extend_from_slice(&buf);
-            v
+            try!(writer.write_u8(0xDA));
+            try!(writer.write_u32::<BigEndian>(len as u32));
         },
         _ => return Err(io::Error::new(io::ErrorKind::Other, "Map too large")),
     };
 
     for (key, entry) in map.iter() {
-        message.append(&mut try!(serialize_string(key)));
-        message.append(&mut try!(entry.serialize()));
+        try!(serialize_string(writer, key));
+        try!(entry.serialize(writer));
     }
 
-    Ok(message)
+    Ok(())
 }
 
-fn get_struct_header(size: i32) -> Result<Vec<u8>, io::Error> {
+fn write_struct_header<W: Write>(writer: &mut W, size: i32) -> Result<(), io::Error> {
     match size {
-        s @ 0 ... 15 => Ok(vec![0xB0 + (s as u8)]),
-        16 ... 255 => Ok(vec![0xDC, size as u8]),
+        s @ 0 ... 15 => try!(writer.write_u8(0xB0 + (s as u8))),
+        16 ... 255 => {
+            try!(writer.write_u8(0xDC));
+            try!(writer.write_u8(size as u8));
+        },
         256 ... 65535 => {
-            let mut buf = [0x0; 2];
-            BigEndian::write_u16(&mut buf, size as u16);
-            let mut v = vec![0xDD];
-            v.extend_from_slice(&buf);
-            Ok(v)
+            try!(writer.write_u8(0xDD));
+            try!(writer.write_u16::<BigEndian>(size as u16));
         },
-        _ => Err(io::Error::new(io::ErrorKind::Other, "Struct too large")),
-    }
+        _ => return Err(io::Error::new(io::ErrorKind::Other, "Struct too large")),
+    };
+
+    Ok(())
 }
 
-fn serialize_node(node_identity: u64, labels: &Vec<&str>, properties: &HashMap<&str, &str>) -> Result<Vec<u8>, io::Error> {
+fn serialize_node<W: Write>(writer: &mut W, node: &Node) -> Result<(), io::Error> {
     let signature = 0x4E;
     let size = 3;
-    let mut message = try!(get_struct_header(size));
 
-    message.push(signature);
-    message.append(&mut try!(serialize_integer(node_identity)));
-    message.append(&mut try!(serialize_list(labels)));
-    message.append(&mut try!(serialize_map(properties)));
+    try!(write_struct_header(writer, size));
+    try!(writer.write_u8(signature));
+    try!(serialize_integer(writer, node.node_identity));
+    try!(serialize_list(writer, &node.labels));
+    try!(serialize_map(writer, &node.properties));
 
-    Ok(message)
+    Ok(())
 }
 
-fn serialize_relationship(rel_identity: u64, start_node_identity: u64, end_node_identity: u64, rel_type: &str, properties: &HashMap<&str, &str>) -> Result<Vec<u8>, io::Error> {
+fn serialize_relationship<W: Write>(writer: &mut W, relationship: &Relationship) -> Result<(), io::Error> {
     let signature = 0x52;
     let size = 5;
-    let mut message = try!(get_struct_header(size));
 
-    message.push(signature);
-    message.append(&mut try!(serialize_integer(rel_identity)));
-    message.append(&mut try!(serialize_integer(start_node_identity)));
-    message.append(&mut try!(serialize_integer(end_node_identity)));
-    message.append(&mut try!(serialize_string(rel_type)));
-    message.append(&mut try!(serialize_map(properties)));
+    try!(write_struct_header(writer, size));
+    try!(writer.write_u8(signature));
+    try!(serialize_integer(writer, relationship.rel_identity));
+    try!(serialize_integer(writer, relationship.start_node_identity));
+    try!(serialize_integer(writer, relationship.end_node_identity));
+    try!(serialize_string(writer, &relationship.rel_type));
+    try!(serialize_map(writer, &relationship.properties));
 
-    Ok(message)
+    Ok(())
 }
 
-fn serialize_path(nodes: &Vec<&str>, relationships: &Vec<&str>, sequence: &Vec<i32>) -> Result<Vec<u8>, io::Error> {
+fn serialize_path<W: Write>(writer: &mut W, path: &Path) -> Result<(), io::Error> {
     let signature = 0x50;
     let size = 3;
-    let mut message = try!(get_struct_header(size));
 
-    message.push(signature);
-    message.append(&mut try!(serialize_list(nodes)));
-    message.append(&mut try!(serialize_list(relationships)));
-    message.append(&mut try!(serialize_list(sequence)));
+    try!(write_struct_header(writer, size));
+    try!(writer.write_u8(signature));
+    try!(serialize_list(writer, &path.nodes));
+    try!(serialize_list(writer, &path.relationships));
+    try!(serialize_list(writer, &path.sequence));
 
-    Ok(message)
+    Ok(())
 }
 
-fn serialize_unbound_relationship(rel_identity: u64, rel_type: &str, properties: &HashMap<&str, &str>) -> Result<Vec<u8>, io::Error> {
+fn serialize_unbound_relationship<W: Write>(writer: &mut W, relationship: &UnboundRelationship) -> Result<(), io::Error> {
     let signature = 0x72;
-    let size = 5;
-    let mut message = try!(get_struct_header(size));
+    let size = 3;
 
-    message.push(signature);
-    message.append(&mut try!(serialize_integer(rel_identity)));
-    message.append(&mut try!(serialize_string(rel_type)));
-    message.append(&mut try!(serialize_map(properties)));
+    try!(write_struct_header(writer, size));
+    try!(writer.write_u8(signature));
+    try!(serialize_integer(writer, relationship.rel_identity));
+    try!(serialize_string(writer, &relationship.rel_type));
+    try!(serialize_map(writer, &relationship.properties));
 
-    Ok(message)
+    Ok(())
 }
 
-fn serialize_init_message(client_name: &str, auth_token: &HashMap<&str, &str>) -> Result<Vec<u8>, io::Error> {
-    let signature = 0x1;
-    let size = 2;
-    let mut message = try!(get_struct_header(size));
-
-    message.push(signature);
-    message.append(&mut try!(serialize_string(client_name)));
-    message.append(&mut try!(serialize_map(auth_token)));
-
-    Ok(message)
-}
+// Generates a `Message` variant and its struct header + field layout from a
+// single table, so the wire signature and field count for each Bolt message
+// live in exactly one place. Each entry is `Name => signature => { field: Type }`.
+macro_rules! bolt_messages {
+    ( $( $name:ident => $signature:expr => { $( $field:ident : $field_type:ty ),* $(,)* } ),* $(,)* ) => {
+        enum Message {
+            $( $name { $( $field: $field_type ),* } ),*
+        }
 
-fn serialize_run_message(statement: &str, parameters: &HashMap<&str, &str>) -> Result<Vec<u8>, io::Error> {
-    let signature = 0x10;
-    let size = 2;
-    let mut message = try!(get_struct_header(size));
+        impl Message {
+            fn serialize(&self) -> Result<Vec<u8>, io::Error> {
+                let mut message = Vec::new();
+
+                match *self {
+                    $(
+                        Message::$name { $( ref $field ),* } => {
+                            let size = 0 $( + bolt_messages!(@one $field) )*;
+                            try!(write_struct_header(&mut message, size));
+                            try!(message.write_u8($signature));
+                            $( try!($field.serialize(&mut message)); )*
+                        }
+                    ),*
+                }
+
+                Ok(message)
+            }
+        }
 
-    message.push(signature);
-    message.append(&mut try!(serialize_string(statement)));
-    message.append(&mut try!(serialize_map(parameters)));
+        fn message_by_signature(signature: u8, buf: &mut impl Read) -> Result<Message, io::Error> {
+            match signature {
+                $(
+                    $signature => Ok(Message::$name {
+                        $( $field: try!(BoltDeserialize::deserialize(buf)) ),*
+                    }),
+                )*
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown message signature")),
+            }
+        }
+    };
 
-    Ok(message)
+    // Expands to `1` for each declared field, so the struct header is sized to
+    // the field count without the caller having to count by hand.
+    (@one $field:ident) => { 1 };
 }
 
-fn serialize_discard_all_message() -> Result<Vec<u8>, io::Error> {
-    let signature = 0x2F;
-    let size = 0;
-    let mut message = try!(get_struct_header(size));
-    message.push(signature);
-    
-    Ok(message)
+bolt_messages! {
+    Init => 0x01 => { client_name: String, auth_token: HashMap<String, BoltValue> },
+    Run => 0x10 => { statement: String, parameters: HashMap<String, BoltValue> },
+    DiscardAll => 0x2F => { },
+    PullAll => 0x3F => { },
+    AckFailure => 0x0E => { },
+    Reset => 0x0F => { },
+    Record => 0x71 => { fields: Vec<BoltValue> },
+    Success => 0x70 => { metadata: HashMap<String, BoltValue> },
+    Failure => 0x7F => { metadata: HashMap<String, BoltValue> },
+    Ignored => 0x7E => { metadata: HashMap<String, BoltValue> },
 }
 
-fn serialize_pull_all_message() -> Result<Vec<u8>, io::Error> {
-    let signature = 0x3F;
-    let size = 0;
-    let mut message = try!(get_struct_header(size));
-    message.push(signature);
-    
-    Ok(message)
+fn read_string(buf: &mut impl Read, len: usize) -> Result<String, io::Error> {
+    let mut bytes = vec![0x0; len];
+    try!(buf.read_exact(&mut bytes[..]));
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid UTF-8 string"))
 }
 
-fn serialize_ack_failure_message() -> Result<Vec<u8>, io::Error> {
-    let signature = 0x0E;
-    let size = 0;
-    let mut message = try!(get_struct_header(size));
-    message.push(signature);
-    
-    Ok(message)
+fn read_list(buf: &mut impl Read, len: usize) -> Result<Vec<BoltValue>, io::Error> {
+    let mut list = Vec::with_capacity(len);
+    for _ in 0..len {
+        list.push(try!(unpack(buf)));
+    }
+    Ok(list)
 }
 
-fn serialize_reset_message() -> Result<Vec<u8>, io::Error> {
-    let signature = 0x0F;
-    let size = 0;
-    let mut message = try!(get_struct_header(size));
-    message.push(signature);
-    
-    Ok(message)
+fn read_map(buf: &mut impl Read, len: usize) -> Result<HashMap<String, BoltValue>, io::Error> {
+    let mut map = HashMap::with_capacity(len);
+    for _ in 0..len {
+        let key = match try!(unpack(buf)) {
+            BoltValue::String(key) => key,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Map key is not a string")),
+        };
+        map.insert(key, try!(unpack(buf)));
+    }
+    Ok(map)
 }
 
-fn serialize_record_message(fields: &HashMap<&str, &str>) -> Result<Vec<u8>, io::Error> {
-    let signature = 0x71;
-    let size = 1;
-    let mut message = try!(get_struct_header(size));
-
-    message.push(signature);
-    message.append(&mut try!(serialize_map(fields)));
-
-    Ok(message)
+fn read_sized<T: Read>(buf: &mut T, size: usize) -> Result<usize, io::Error> {
+    let length = match size {
+        1 => try!(buf.read_u8()) as usize,
+        2 => try!(buf.read_u16::<BigEndian>()) as usize,
+        _ => try!(buf.read_u32::<BigEndian>()) as usize,
+    };
+    Ok(length)
 }
 
-fn serialize_success_message(metadata: &HashMap<&str, &str>) -> Result<Vec<u8>, io::Error> {
-    let signature = 0x70;
-    let size = 1;
-    let mut message = try!(get_struct_header(size));
-
-    message.push(signature);
-    message.append(&mut try!(serialize_map(metadata)));
-
-    Ok(message)
+fn unpack(buf: &mut impl Read) -> Result<BoltValue, io::Error> {
+    let marker = try!(buf.read_u8());
+
+    match marker {
+        0x00 ... 0x7F => Ok(BoltValue::Integer(marker as i64)),
+        0xF0 ... 0xFF => Ok(BoltValue::Integer(marker as i8 as i64)),
+        0xC0 => Ok(BoltValue::Null),
+        0xC2 => Ok(BoltValue::Boolean(false)),
+        0xC3 => Ok(BoltValue::Boolean(true)),
+        0xC1 => Ok(BoltValue::Float(try!(buf.read_f64::<BigEndian>()))),
+        0xC8 => Ok(BoltValue::Integer(try!(buf.read_i8()) as i64)),
+        0xC9 => Ok(BoltValue::Integer(try!(buf.read_i16::<BigEndian>()) as i64)),
+        0xCA => Ok(BoltValue::Integer(try!(buf.read_i32::<BigEndian>()) as i64)),
+        0xCB => Ok(BoltValue::Integer(try!(buf.read_i64::<BigEndian>()))),
+        0x80 ... 0x8F => {
+            let len = (marker & 0x0F) as usize;
+            Ok(BoltValue::String(try!(read_string(buf, len))))
+        },
+        0xD0 => { let len = try!(read_sized(buf, 1)); Ok(BoltValue::String(try!(read_string(buf, len)))) },
+        0xD1 => { let len = try!(read_sized(buf, 2)); Ok(BoltValue::String(try!(read_string(buf, len)))) },
+        0xD2 => { let len = try!(read_sized(buf, 4)); Ok(BoltValue::String(try!(read_string(buf, len)))) },
+        0x90 ... 0x9F => {
+            let len = (marker & 0x0F) as usize;
+            Ok(BoltValue::List(try!(read_list(buf, len))))
+        },
+        0xD4 => { let len = try!(read_sized(buf, 1)); Ok(BoltValue::List(try!(read_list(buf, len)))) },
+        0xD5 => { let len = try!(read_sized(buf, 2)); Ok(BoltValue::List(try!(read_list(buf, len)))) },
+        0xD6 => { let len = try!(read_sized(buf, 4)); Ok(BoltValue::List(try!(read_list(buf, len)))) },
+        0xA0 ... 0xAF => {
+            let len = (marker & 0x0F) as usize;
+            Ok(BoltValue::Map(try!(read_map(buf, len))))
+        },
+        0xD8 => { let len = try!(read_sized(buf, 1)); Ok(BoltValue::Map(try!(read_map(buf, len)))) },
+        0xD9 => { let len = try!(read_sized(buf, 2)); Ok(BoltValue::Map(try!(read_map(buf, len)))) },
+        0xDA => { let len = try!(read_sized(buf, 4)); Ok(BoltValue::Map(try!(read_map(buf, len)))) },
+        0xB0 ... 0xBF => {
+            let len = (marker & 0x0F) as usize;
+            let signature = try!(buf.read_u8());
+            Ok(BoltValue::Structure(signature, try!(read_list(buf, len))))
+        },
+        0xDC => {
+            let len = try!(read_sized(buf, 1));
+            let signature = try!(buf.read_u8());
+            Ok(BoltValue::Structure(signature, try!(read_list(buf, len))))
+        },
+        0xDD => {
+            let len = try!(read_sized(buf, 2));
+            let signature = try!(buf.read_u8());
+            Ok(BoltValue::Structure(signature, try!(read_list(buf, len))))
+        },
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown PackStream marker")),
+    }
 }
 
-fn serialize_failure_message(metadata: &HashMap<&str, &str>) -> Result<Vec<u8>, io::Error> {
-    let signature = 0x7F;
-    let size = 1;
-    let mut message = try!(get_struct_header(size));
+fn decode_message(bytes: &[u8]) -> Result<Message, io::Error> {
+    let mut cursor = io::Cursor::new(bytes);
+    let marker = try!(cursor.read_u8());
+    match marker {
+        0xB0 ... 0xBF => {},
+        0xDC => { try!(read_sized(&mut cursor, 1)); },
+        0xDD => { try!(read_sized(&mut cursor, 2)); },
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected a message structure")),
+    };
+    let signature = try!(cursor.read_u8());
+    message_by_signature(signature, &mut cursor)
+}
 
-    message.push(signature);
-    message.append(&mut try!(serialize_map(metadata)));
+pub struct RecordStream<'a, S: Read + Write + 'a> {
+    session: &'a mut BoltSession<S>,
+    fields: Vec<String>,
+    done: bool,
+}
 
-    Ok(message)
+impl<'a, S: Read + Write> RecordStream<'a, S> {
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
 }
 
-fn serialize_ignored_message(metadata: &HashMap<&str, &str>) -> Result<Vec<u8>, io::Error> {
-    let signature = 0x7E;
-    let size = 1;
-    let mut message = try!(get_struct_header(size));
+impl<'a, S: Read + Write> Iterator for RecordStream<'a, S> {
+    type Item = Result<Vec<BoltValue>, BoltError>;
 
-    message.push(signature);
-    message.append(&mut try!(serialize_map(metadata)));
+    fn next(&mut self) -> Option<Result<Vec<BoltValue>, BoltError>> {
+        if self.done {
+            return None;
+        }
 
-    Ok(message)
+        let message = match self.session.read_typed_message() {
+            Ok(message) => message,
+            Err(error) => {
+                self.done = true;
+                return Some(Err(BoltError::from(error)));
+            },
+        };
+
+        match message {
+            Message::Record { fields } => Some(Ok(fields)),
+            Message::Success { .. } => {
+                self.done = true;
+                None
+            },
+            Message::Failure { metadata } => {
+                self.done = true;
+                Some(Err(BoltError::Failure(metadata)))
+            },
+            _ => {
+                self.done = true;
+                Some(Err(BoltError::Protocol(String::from("Unexpected message in record stream"))))
+            },
+        }
+    }
 }
 
-pub struct BoltSession {
-    stream: TcpStream,
+pub struct BoltSession<S: Read + Write> {
+    stream: S,
+    version: u32,
 }
 
-impl BoltSession {
-    fn new(mut stream: TcpStream) -> Result<BoltSession, io::Error> {
-        try!(BoltSession::handshake(&mut stream));
+impl<S: Read + Write> BoltSession<S> {
+    fn new(mut stream: S) -> Result<BoltSession<S>, BoltError> {
+        let version = try!(BoltSession::handshake(&mut stream));
 
-        Ok(BoltSession { stream: stream })
+        Ok(BoltSession { stream: stream, version: version })
     }
 
-    fn handshake(mut stream: &TcpStream) -> Result<(), io::Error> {
+    fn handshake(stream: &mut S) -> Result<u32, BoltError> {
         // send preamble
         try!(stream.write(&BOLT_PREAMBLE));
 
-        // send compatible versions
-        for &version in BOLT_SUPPORTED_VERSIONS.into_iter() {
-            let mut buf = [0x0; 4];
-            BigEndian::write_u32(&mut buf, version);
-            try!(stream.write(&buf));
-        }
-
-        // fill remaining spaces with 'none' version
-        for _ in BOLT_SUPPORTED_VERSIONS.len()..4 {
-            let mut buf = [0x0; 4];
-            BigEndian::write_u32(&mut buf, BOLT_VERSION_NONE);
-            try!(stream.write(&buf));
+        // offer our supported versions, one 4-byte slot each, padding any
+        // unused slots with `BOLT_VERSION_NONE`
+        for slot in 0..BOLT_HANDSHAKE_SLOTS {
+            let version = BOLT_SUPPORTED_VERSIONS.get(slot).cloned().unwrap_or(BOLT_VERSION_NONE);
+            try!(stream.write_u32::<BigEndian>(version));
         }
 
-        let mut response_buffer = [0x0; 4];
-        try!(stream.read_exact(&mut response_buffer));
+        let version = try!(stream.read_u32::<BigEndian>());
 
-        let version = BigEndian::read_u32(&response_buffer);
-
-        if version == 0 {
-            panic!("No supported versions; Exiting.");
+        if version == BOLT_VERSION_NONE {
+            return Err(BoltError::NoSupportedVersion);
         }
 
-        println!("Using version {}", version);
-
-        Ok(())
+        Ok(version)
     }
 
     fn send_message(&mut self, message: &[u8]) -> Result<(), io::Error> {
@@ -477,15 +693,9 @@ impl BoltSession {
         println!("Writing message:\n{}", pretty_message);
 
         for chunk in message.chunks(std::u16::MAX as usize) {
-            let chunk_size = chunk.len() as u16;
-            let mut buf = [0x0; 2];
-            BigEndian::write_u16(&mut buf, chunk_size);
-
-            try!(self.stream.write(&buf));
-            try!(self.stream.write(chunk));
-
-            let buf = [0x0; 2];
-            try!(self.stream.write(&buf));
+            try!(self.stream.write_u16::<BigEndian>(chunk.len() as u16));
+            try!(self.stream.write_all(chunk));
+            try!(self.stream.write_u16::<BigEndian>(0));
         }
 
         Ok(())
@@ -499,9 +709,7 @@ impl BoltSession {
 
         loop {
             // read header
-            let mut buf = [0x0; 2];
-            try!(self.stream.read_exact(&mut buf));
-            message_length = BigEndian::read_u16(&buf);
+            message_length = try!(self.stream.read_u16::<BigEndian>());
 
             if message_length == 0 { break };
 
@@ -519,40 +727,298 @@ impl BoltSession {
         Ok(message)
     }
 
-    fn init(&mut self) -> Result<(), io::Error> {
-        let mut map = HashMap::new();
-        map.insert("scheme", "basic");
-        map.insert("principal", "neo4j");
-        map.insert("credentials", "password");
+    fn read_typed_message(&mut self) -> Result<Message, io::Error> {
+        let bytes = try!(self.read_message());
+        decode_message(&bytes)
+    }
 
-        let init_message = try!(serialize_init_message("MyClient/1.0", &map));
+    fn init(&mut self, username: &str, password: &str) -> Result<(), BoltError> {
+        let init_message = if self.version >= 3 {
+            // Bolt v3 replaced INIT with HELLO: a single metadata map that folds
+            // the user agent in with the auth fields.
+            let mut metadata = HashMap::new();
+            metadata.insert(String::from("user_agent"), BoltValue::String(String::from("MyClient/1.0")));
+            metadata.insert(String::from("scheme"), BoltValue::String(String::from("basic")));
+            metadata.insert(String::from("principal"), BoltValue::String(String::from(username)));
+            metadata.insert(String::from("credentials"), BoltValue::String(String::from(password)));
+
+            let mut message = Vec::new();
+            try!(write_struct_header(&mut message, 1));
+            try!(message.write_u8(0x01));
+            try!(serialize_map(&mut message, &metadata));
+            message
+        } else {
+            let mut auth_token = HashMap::new();
+            auth_token.insert(String::from("scheme"), BoltValue::String(String::from("basic")));
+            auth_token.insert(String::from("principal"), BoltValue::String(String::from(username)));
+            auth_token.insert(String::from("credentials"), BoltValue::String(String::from(password)));
+
+            try!(Message::Init {
+                client_name: String::from("MyClient/1.0"),
+                auth_token: auth_token,
+            }.serialize())
+        };
 
         try!(self.send_message(&init_message[..]));
-        
-        let message = try!(self.read_message());
 
-        Ok(())
+        match try!(self.read_typed_message()) {
+            Message::Success { .. } => Ok(()),
+            Message::Failure { metadata } => Err(BoltError::Failure(metadata)),
+            _ => Err(BoltError::Protocol(String::from("Unexpected response to INIT/HELLO"))),
+        }
     }
 
-    pub fn run(&mut self, statement: String) -> Result<(), io::Error> {
-        let parameters = HashMap::<&str, &str>::new();
+    pub fn run(&mut self, statement: String) -> Result<RecordStream<S>, BoltError> {
+        let parameters = HashMap::<String, BoltValue>::new();
 
-        let run_message = try!(serialize_run_message(&statement, &parameters));
+        let run_message = try!(Message::Run {
+            statement: statement,
+            parameters: parameters,
+        }.serialize());
 
         try!(self.send_message(&run_message[..]));
 
-        let message = try!(self.read_message());
+        // The RUN summary carries the column names under the `fields` key; a
+        // FAILURE here means the statement was rejected before streaming began.
+        let fields = match try!(self.read_typed_message()) {
+            Message::Success { metadata } => extract_fields(&metadata),
+            Message::Failure { metadata } => return Err(BoltError::Failure(metadata)),
+            _ => return Err(BoltError::Protocol(String::from("Unexpected response to RUN"))),
+        };
 
-        Ok(())
+        let pull_all_message = try!(Message::PullAll {}.serialize());
+        try!(self.send_message(&pull_all_message[..]));
+
+        Ok(RecordStream { session: self, fields: fields, done: false })
+    }
+}
+
+fn extract_fields(metadata: &HashMap<String, BoltValue>) -> Vec<String> {
+    let mut fields = Vec::new();
+
+    if let Some(&BoltValue::List(ref values)) = metadata.get("fields") {
+        for value in values {
+            if let BoltValue::String(ref name) = *value {
+                fields.push(name.clone());
+            }
+        }
     }
+
+    fields
+}
+
+pub fn connect(server: &str, username: &str, password: &str) -> Result<BoltSession<TcpStream>, BoltError> {
+    let stream = try!(TcpStream::connect(server));
+    try!(stream.set_read_timeout(Some(Duration::new(5, 0))));
+
+    let mut session = try!(BoltSession::new(stream));
+    try!(session.init(username, password));
+
+    Ok(session)
 }
 
-pub fn connect(server: &str, username: &str, password: &str) -> Result<BoltSession, io::Error> {
+pub fn connect_tls(server: &str, domain: &str, username: &str, password: &str) -> Result<BoltSession<TlsStream<TcpStream>>, BoltError> {
+    let connector = try!(TlsConnector::new().map_err(|error| io::Error::new(io::ErrorKind::Other, error)));
+
     let stream = try!(TcpStream::connect(server));
     try!(stream.set_read_timeout(Some(Duration::new(5, 0))));
 
+    let stream = try!(connector.connect(domain, stream).map_err(|error| io::Error::new(io::ErrorKind::Other, error)));
+
     let mut session = try!(BoltSession::new(stream));
-    try!(session.init());
+    try!(session.init(username, password));
 
     Ok(session)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn serialize_to_vec<T: BoltSerialize>(value: &T) -> Vec<u8> {
+        let mut buf = Vec::new();
+        value.serialize(&mut buf).unwrap();
+        buf
+    }
+
+    fn roundtrip(value: BoltValue) -> BoltValue {
+        let bytes = serialize_to_vec(&value);
+        unpack(&mut Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn tiny_int_upper_boundary() {
+        let bytes = serialize_to_vec(&127i64);
+        assert_eq!(bytes, vec![0x7F]);
+        match roundtrip(BoltValue::Integer(127)) {
+            BoltValue::Integer(127) => {},
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn int16_lower_boundary_above_tiny_int() {
+        let bytes = serialize_to_vec(&128i64);
+        assert_eq!(bytes[0], 0xC9);
+        match roundtrip(BoltValue::Integer(128)) {
+            BoltValue::Integer(128) => {},
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tiny_int_lower_boundary() {
+        let bytes = serialize_to_vec(&-16i64);
+        assert_eq!(bytes, vec![0xF0]);
+        match roundtrip(BoltValue::Integer(-16)) {
+            BoltValue::Integer(-16) => {},
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn int8_upper_boundary_below_tiny_int() {
+        let bytes = serialize_to_vec(&-17i64);
+        assert_eq!(bytes, vec![0xC8, 0xEF]);
+        match roundtrip(BoltValue::Integer(-17)) {
+            BoltValue::Integer(-17) => {},
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unpack_reads_negative_tiny_int_marker_directly() {
+        // 0xF0..=0xFF is the negative half of the tiny-int marker range; the
+        // marker byte itself is the value, sign-extended as an i8.
+        match unpack(&mut Cursor::new(vec![0xF5])).unwrap() {
+            BoltValue::Integer(-11) => {},
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn string_size_tiers_roundtrip() {
+        for &len in &[0usize, 15, 16, 255, 256, 65535, 65536] {
+            let s = "a".repeat(len);
+            let bytes = serialize_to_vec(&BoltValue::String(s.clone()));
+            match len {
+                0...15 => assert_eq!(bytes[0], 0x80 + (len as u8)),
+                16...255 => assert_eq!(bytes[0], 0xD0),
+                256...65535 => assert_eq!(bytes[0], 0xD1),
+                _ => assert_eq!(bytes[0], 0xD2),
+            }
+
+            match unpack(&mut Cursor::new(bytes)).unwrap() {
+                BoltValue::String(value) => assert_eq!(value, s),
+                other => panic!("unexpected value: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn list_size_tiers_roundtrip() {
+        for &len in &[0usize, 15, 16, 255, 256, 65535, 65536] {
+            let list: Vec<BoltValue> = (0..len).map(|_| BoltValue::Null).collect();
+            let bytes = serialize_to_vec(&BoltValue::List(list));
+            match len {
+                0...15 => assert_eq!(bytes[0], 0x90 + (len as u8)),
+                16...255 => assert_eq!(bytes[0], 0xD4),
+                256...65535 => assert_eq!(bytes[0], 0xD5),
+                _ => assert_eq!(bytes[0], 0xD6),
+            }
+
+            match unpack(&mut Cursor::new(bytes)).unwrap() {
+                BoltValue::List(values) => assert_eq!(values.len(), len),
+                other => panic!("unexpected value: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn map_size_tiers_roundtrip() {
+        for &len in &[0usize, 15, 16, 255, 256, 65535, 65536] {
+            let mut map = HashMap::new();
+            for i in 0..len {
+                map.insert(format!("k{}", i), BoltValue::Null);
+            }
+            let bytes = serialize_to_vec(&BoltValue::Map(map));
+            match len {
+                0...15 => assert_eq!(bytes[0], 0xA0 + (len as u8)),
+                16...255 => assert_eq!(bytes[0], 0xD8),
+                256...65535 => assert_eq!(bytes[0], 0xD9),
+                _ => assert_eq!(bytes[0], 0xDA),
+            }
+
+            match unpack(&mut Cursor::new(bytes)).unwrap() {
+                BoltValue::Map(values) => assert_eq!(values.len(), len),
+                other => panic!("unexpected value: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn unpack_decodes_tiny_struct() {
+        let mut bytes = Vec::new();
+        write_struct_header(&mut bytes, 1).unwrap();
+        bytes.write_u8(0x4E).unwrap();
+        bytes.write_u8(1).unwrap();
+
+        match unpack(&mut Cursor::new(bytes)).unwrap() {
+            BoltValue::Structure(0x4E, fields) => {
+                assert_eq!(fields.len(), 1);
+            },
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn run_message_roundtrips_through_the_macro() {
+        let mut parameters = HashMap::new();
+        parameters.insert(String::from("limit"), BoltValue::Integer(10));
+
+        let message = Message::Run {
+            statement: String::from("RETURN 1"),
+            parameters: parameters,
+        };
+
+        let bytes = message.serialize().unwrap();
+
+        match decode_message(&bytes).unwrap() {
+            Message::Run { statement, parameters } => {
+                assert_eq!(statement, "RETURN 1");
+                assert_eq!(parameters.len(), 1);
+            },
+            _ => panic!("expected a Run message"),
+        }
+    }
+
+    #[test]
+    fn decode_message_reconstructs_record() {
+        let mut bytes = Vec::new();
+        write_struct_header(&mut bytes, 1).unwrap();
+        bytes.write_u8(0x71).unwrap();
+        BoltValue::List(vec![BoltValue::Integer(42)]).serialize(&mut bytes).unwrap();
+
+        match decode_message(&bytes).unwrap() {
+            Message::Record { fields } => assert_eq!(fields.len(), 1),
+            _ => panic!("expected a Record message"),
+        }
+    }
+
+    #[test]
+    fn decode_message_reconstructs_success() {
+        let mut fields = HashMap::new();
+        fields.insert(String::from("fields"), BoltValue::List(vec![BoltValue::String(String::from("n"))]));
+
+        let mut bytes = Vec::new();
+        write_struct_header(&mut bytes, 1).unwrap();
+        bytes.write_u8(0x70).unwrap();
+        BoltValue::Map(fields).serialize(&mut bytes).unwrap();
+
+        match decode_message(&bytes).unwrap() {
+            Message::Success { metadata } => assert!(metadata.contains_key("fields")),
+            _ => panic!("expected a Success message"),
+        }
+    }
+}